@@ -0,0 +1,72 @@
+use crate::time::clock::Clock;
+
+cfg_test_util! {
+    use crate::loom::sync::Arc;
+    use crate::time::clock::ClockSource;
+}
+
+/// Builds a Tokio `Runtime` with custom configuration values.
+///
+/// Methods can be chained in order to set the configuration values. The
+/// `Runtime` is constructed by calling `build`.
+///
+/// This only covers the time-related configuration (clock source,
+/// start-paused); the full `Builder` also configures the number of worker
+/// threads, the I/O driver, the blocking pool, and so on.
+pub struct Builder {
+    /// Whether this builder is for the `current_thread` runtime, the only
+    /// flavor on which pausing time is supported.
+    pub(crate) enable_pausing: bool,
+
+    /// Whether to start the runtime with time already paused.
+    pub(crate) start_paused: bool,
+
+    /// Custom source of time to install on the runtime's clock, if any.
+    #[cfg(feature = "test-util")]
+    pub(crate) clock_source: Option<Arc<dyn ClockSource>>,
+}
+
+impl Builder {
+    pub(crate) fn new(enable_pausing: bool) -> Builder {
+        Builder {
+            enable_pausing,
+            start_paused: false,
+            #[cfg(feature = "test-util")]
+            clock_source: None,
+        }
+    }
+
+    /// Install a custom [`ClockSource`] on the runtime being built.
+    ///
+    /// Unlike [`crate::time::set_source`], which installs a source on an
+    /// already-running runtime, this wires the source in before the
+    /// runtime starts spawning tasks, so there is no window in which
+    /// `Instant::now()` reads the system clock. This is the installation
+    /// path deterministic simulators should use.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let rt = tokio::runtime::Builder::new_current_thread()
+    ///     .clock_source(my_simulated_clock)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "test-util")]
+    pub fn clock_source(&mut self, source: impl ClockSource + 'static) -> &mut Self {
+        self.clock_source = Some(Arc::new(source));
+        self
+    }
+
+    /// Construct the `Clock` this builder's runtime should use, consuming
+    /// whatever `ClockSource` was installed via [`Builder::clock_source`].
+    pub(crate) fn build_clock(&mut self) -> Clock {
+        #[cfg(feature = "test-util")]
+        {
+            return Clock::new_with_source(self.enable_pausing, self.start_paused, self.clock_source.take());
+        }
+
+        #[cfg(not(feature = "test-util"))]
+        Clock::new(self.enable_pausing, self.start_paused)
+    }
+}
@@ -0,0 +1,17 @@
+//! The Tokio runtime.
+//!
+//! Unlike other Rust programs, asynchronous applications require runtime
+//! support. In particular, the following runtime services are necessary:
+//!
+//! * An I/O event loop, called the driver, which drives I/O resources and
+//!   dispatches I/O events to tasks that depend on them.
+//! * A scheduler to execute tasks that use these I/O resources.
+//! * A timer for scheduling work to run after a set period of time.
+//!
+//! Tokio's `Runtime` bundles all of these services as a single type,
+//! allowing them to be started, shut down, and configured together. A
+//! `Runtime` instance can be built with [`Builder`].
+
+pub(crate) mod builder;
+
+pub use self::builder::Builder;
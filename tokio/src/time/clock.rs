@@ -6,9 +6,9 @@
 //! `test-util` feature flag is enabled, the values returned for `now()` are
 //! configurable.
 
-cfg_not_test_util! {
-    use crate::time::{Instant};
+use crate::time::Instant;
 
+cfg_not_test_util! {
     #[derive(Debug, Clone)]
     pub(crate) struct Clock {}
 
@@ -28,8 +28,73 @@ cfg_not_test_util! {
 }
 
 cfg_test_util! {
-    use crate::time::{Duration, Instant};
+    use crate::time::Duration;
     use crate::loom::sync::{Arc, Mutex};
+    use std::fmt;
+
+    /// A pluggable source of time for a Tokio runtime.
+    ///
+    /// By default, a runtime's [`Clock`] reads `std::time::Instant::now()`,
+    /// or the built-in pausable test clock once paused with [`pause`].
+    /// Implementing this trait and installing it with [`set_source`] lets a
+    /// deterministic simulator drive Tokio's time (`time::sleep`,
+    /// [`Interval`], timeouts, ...) from a single externally-controlled
+    /// virtual clock instead.
+    ///
+    /// `now()` is consulted on every call, so the simulator is free to
+    /// return any monotonic `Instant` it wants, including stepping the
+    /// clock forward from its own scheduler loop.
+    ///
+    /// `now()` must not itself read Tokio's current time (for example by
+    /// creating a [`Sleep`] or calling `Instant::now()`): doing so panics.
+    /// Functions that only inspect or change the clock's state, such as
+    /// [`is_paused`], do not re-enter the source and can be called.
+    ///
+    /// [`Interval`]: crate::time::Interval
+    /// [`Sleep`]: crate::time::Sleep
+    pub trait ClockSource: std::fmt::Debug + Send + Sync {
+        /// Returns the current instant, as tracked by this source.
+        fn now(&self) -> Instant;
+    }
+
+    /// An error returned by the fallible [`try_pause`], [`try_resume`], and
+    /// [`try_advance`] clock functions.
+    ///
+    /// Unlike [`pause`], [`resume`], and [`advance`], which panic when the
+    /// clock is not in the expected state, the `try_*` functions return
+    /// this error instead. This makes them safe to call from library code
+    /// and generic test helpers that cannot guarantee ahead of time which
+    /// state the clock is in.
+    #[derive(Debug)]
+    pub struct ClockError {
+        kind: ClockErrorKind,
+    }
+
+    #[derive(Debug)]
+    enum ClockErrorKind {
+        NoRuntime,
+        NotCurrentThread,
+        AlreadyPaused,
+        NotPaused,
+    }
+
+    impl fmt::Display for ClockError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self.kind {
+                ClockErrorKind::NoRuntime => {
+                    "time cannot be frozen from outside the Tokio runtime"
+                }
+                ClockErrorKind::NotCurrentThread => {
+                    "`time::pause()` requires the `current_thread` Tokio runtime. \
+                     This is the default Runtime used by `#[tokio::test]."
+                }
+                ClockErrorKind::AlreadyPaused => "time is already frozen",
+                ClockErrorKind::NotPaused => "time is not frozen",
+            })
+        }
+    }
+
+    impl std::error::Error for ClockError {}
 
     cfg_rt! {
         fn clock() -> Option<Clock> {
@@ -59,6 +124,14 @@ cfg_test_util! {
 
         /// Instant at which the clock was last unfrozen
         unfrozen: Option<std::time::Instant>,
+
+        /// Rate at which the clock advances relative to wall-clock time
+        /// while unfrozen. `1.0` is real time, `2.0` is twice as fast, etc.
+        rate: f64,
+
+        /// Custom source of time installed in place of the system clock, if
+        /// any. When set, it takes priority over `base`/`unfrozen`.
+        source: Option<Arc<dyn ClockSource>>,
     }
 
     /// Pause time
@@ -101,6 +174,39 @@ cfg_test_util! {
         clock.pause();
     }
 
+    /// Pause time, returning an error instead of panicking if time cannot
+    /// be paused.
+    ///
+    /// This is the fallible counterpart to [`pause`]; see its
+    /// documentation for details on what pausing time does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if time is already frozen, or if called from
+    /// outside of a `current_thread` Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() {
+    ///     assert!(!tokio::time::is_paused());
+    ///
+    ///     tokio::time::try_pause().unwrap();
+    ///     assert!(tokio::time::is_paused());
+    ///
+    ///     // Pausing again fails instead of panicking.
+    ///     assert!(tokio::time::try_pause().is_err());
+    ///
+    ///     tokio::time::try_resume().unwrap();
+    ///     assert!(!tokio::time::is_paused());
+    /// }
+    /// ```
+    pub fn try_pause() -> Result<(), ClockError> {
+        let clock = clock().ok_or(ClockError { kind: ClockErrorKind::NoRuntime })?;
+        clock.try_pause()
+    }
+
     /// Resume time
     ///
     /// Clears the saved `Instant::now()` value. Subsequent calls to
@@ -112,13 +218,21 @@ cfg_test_util! {
     /// runtime.
     pub fn resume() {
         let clock = clock().expect("time cannot be frozen from outside the Tokio runtime");
-        let mut inner = clock.inner.lock();
-
-        if inner.unfrozen.is_some() {
-            panic!("time is not frozen");
-        }
+        clock.try_resume().unwrap_or_else(|e| panic!("{}", e));
+    }
 
-        inner.unfrozen = Some(std::time::Instant::now());
+    /// Resume time, returning an error instead of panicking if time is not
+    /// paused.
+    ///
+    /// This is the fallible counterpart to [`resume`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if time is not frozen, or if called from outside
+    /// of the Tokio runtime.
+    pub fn try_resume() -> Result<(), ClockError> {
+        let clock = clock().ok_or(ClockError { kind: ClockErrorKind::NoRuntime })?;
+        clock.try_resume()
     }
 
     /// Advance time
@@ -150,6 +264,80 @@ cfg_test_util! {
         crate::task::yield_now().await;
     }
 
+    /// Advance time, returning an error instead of panicking if time is
+    /// not paused.
+    ///
+    /// This is the fallible counterpart to [`advance`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if time is not frozen, or if called from outside
+    /// of the Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tokio::time::Duration;
+    ///
+    /// #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// async fn main() {
+    ///     tokio::time::try_advance(Duration::from_secs(1)).await.unwrap();
+    /// }
+    /// ```
+    pub async fn try_advance(duration: Duration) -> Result<(), ClockError> {
+        let clock = clock().ok_or(ClockError { kind: ClockErrorKind::NoRuntime })?;
+        clock.try_advance(duration)?;
+
+        crate::task::yield_now().await;
+        Ok(())
+    }
+
+    /// Returns `true` if time is currently paused, `false` otherwise.
+    ///
+    /// Unlike [`pause`] and friends, this never panics: it simply returns
+    /// `false` when called from outside of the Tokio runtime.
+    pub fn is_paused() -> bool {
+        clock().map_or(false, |clock| clock.is_paused())
+    }
+
+    /// Scale the rate at which time advances when it is not frozen.
+    ///
+    /// A `rate` of `1.0` is real time (the default), `2.0` makes one real
+    /// second appear as two Tokio seconds, and `0.5` slows the clock down
+    /// to half speed. This only affects `Instant::now()` while the clock is
+    /// unfrozen; it has no effect on the amount [`advance`] adds while the
+    /// clock is paused.
+    ///
+    /// This is useful for integration tests that depend on timers but
+    /// cannot be fully paused, for example because they perform real I/O,
+    /// yet still want to run much faster than wall time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of the Tokio runtime, or if `rate` is
+    /// negative or not finite.
+    ///
+    /// [`advance`]: fn@crate::time::advance
+    pub fn set_time_scale(rate: f64) {
+        let clock = clock().expect("time scale cannot be set from outside the Tokio runtime");
+        clock.set_time_scale(rate);
+    }
+
+    /// Install a custom [`ClockSource`] on the current runtime's clock.
+    ///
+    /// After this call, `Instant::now()` delegates to `source` instead of
+    /// the system clock or the pause/advance/time-scale machinery above.
+    /// This is the installation path deterministic simulators use to drive
+    /// all of Tokio's time from their own externally-controlled clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called from outside of the Tokio runtime.
+    pub fn set_source(source: impl ClockSource + 'static) {
+        let clock = clock().expect("clock source cannot be set from outside the Tokio runtime");
+        clock.set_source(Arc::new(source));
+    }
+
     /// Return the current instant, factoring in frozen time.
     pub(crate) fn now() -> Instant {
         if let Some(clock) = clock() {
@@ -159,10 +347,51 @@ cfg_test_util! {
         }
     }
 
+    std::thread_local! {
+        static IN_CLOCK_SOURCE: std::cell::Cell<bool> = std::cell::Cell::new(false);
+    }
+
+    /// Marks the current thread as being inside a [`ClockSource::now`] call
+    /// for the lifetime of the guard, panicking if one is already in
+    /// progress. Resets on drop, including on unwind, so a panicking
+    /// `now()` doesn't wedge the flag for the rest of the thread.
+    struct ReentrancyGuard;
+
+    impl ReentrancyGuard {
+        fn enter() -> Self {
+            IN_CLOCK_SOURCE.with(|in_source| {
+                assert!(
+                    !in_source.replace(true),
+                    "a ClockSource::now() implementation called back into Tokio's time"
+                );
+            });
+            ReentrancyGuard
+        }
+    }
+
+    impl Drop for ReentrancyGuard {
+        fn drop(&mut self) {
+            IN_CLOCK_SOURCE.with(|in_source| in_source.set(false));
+        }
+    }
+
     impl Clock {
         /// Return a new `Clock` instance that uses the current execution context's
         /// source of time.
         pub(crate) fn new(enable_pausing: bool, start_paused: bool) -> Clock {
+            Clock::new_with_source(enable_pausing, start_paused, None)
+        }
+
+        /// Like [`Clock::new`], but installs `source` as the clock's
+        /// [`ClockSource`] before the runtime it belongs to starts
+        /// spawning tasks, instead of leaving a window in which
+        /// `Instant::now()` reads the system clock until [`set_source`] is
+        /// called. This is what `Builder::clock_source` wires into.
+        pub(crate) fn new_with_source(
+            enable_pausing: bool,
+            start_paused: bool,
+            source: Option<Arc<dyn ClockSource>>,
+        ) -> Clock {
             let now = std::time::Instant::now();
 
             let clock = Clock {
@@ -170,6 +399,8 @@ cfg_test_util! {
                     enable_pausing,
                     base: now,
                     unfrozen: Some(now),
+                    rate: 1.0,
+                    source,
                 })),
             };
 
@@ -181,17 +412,34 @@ cfg_test_util! {
         }
 
         pub(crate) fn pause(&self) {
+            self.try_pause().unwrap_or_else(|e| panic!("{}", e));
+        }
+
+        pub(crate) fn try_pause(&self) -> Result<(), ClockError> {
             let mut inner = self.inner.lock();
 
             if !inner.enable_pausing {
-                drop(inner); // avoid poisoning the lock
-                panic!("`time::pause()` requires the `current_thread` Tokio runtime. \
-                        This is the default Runtime used by `#[tokio::test].");
+                return Err(ClockError { kind: ClockErrorKind::NotCurrentThread });
             }
 
-            let elapsed = inner.unfrozen.as_ref().expect("time is already frozen").elapsed();
-            inner.base += elapsed;
+            let unfrozen = inner
+                .unfrozen
+                .ok_or(ClockError { kind: ClockErrorKind::AlreadyPaused })?;
+
+            inner.base += scale(unfrozen.elapsed(), inner.rate);
             inner.unfrozen = None;
+            Ok(())
+        }
+
+        pub(crate) fn try_resume(&self) -> Result<(), ClockError> {
+            let mut inner = self.inner.lock();
+
+            if inner.unfrozen.is_some() {
+                return Err(ClockError { kind: ClockErrorKind::NotPaused });
+            }
+
+            inner.unfrozen = Some(std::time::Instant::now());
+            Ok(())
         }
 
         pub(crate) fn is_paused(&self) -> bool {
@@ -199,26 +447,211 @@ cfg_test_util! {
             inner.unfrozen.is_none()
         }
 
+        pub(crate) fn set_time_scale(&self, rate: f64) {
+            assert!(
+                rate.is_finite() && rate >= 0.0,
+                "time scale rate must be a non-negative, finite number"
+            );
+
+            let mut inner = self.inner.lock();
+
+            // Re-base onto the rate in effect up to this point, so that
+            // `now()` only ever applies the new rate to elapsed time
+            // measured from here forward. Otherwise, changing `rate` would
+            // retroactively rescale time already elapsed under the old
+            // rate and could make `now()` jump backwards.
+            if let Some(unfrozen) = inner.unfrozen {
+                let elapsed = unfrozen.elapsed();
+                inner.base += scale(elapsed, inner.rate);
+                inner.unfrozen = Some(std::time::Instant::now());
+            }
+
+            inner.rate = rate;
+        }
+
+        pub(crate) fn set_source(&self, source: Arc<dyn ClockSource>) {
+            let mut inner = self.inner.lock();
+            inner.source = Some(source);
+        }
+
         pub(crate) fn advance(&self, duration: Duration) {
+            self.try_advance(duration).unwrap_or_else(|e| panic!("{}", e));
+        }
+
+        pub(crate) fn try_advance(&self, duration: Duration) -> Result<(), ClockError> {
             let mut inner = self.inner.lock();
 
             if inner.unfrozen.is_some() {
-                panic!("time is not frozen");
+                return Err(ClockError { kind: ClockErrorKind::NotPaused });
             }
 
             inner.base += duration;
+            Ok(())
         }
 
         pub(crate) fn now(&self) -> Instant {
             let inner = self.inner.lock();
 
+            // Clone the source out and drop the lock before calling into
+            // it, so that a `ClockSource::now()` which reads other state
+            // guarded by this same mutex (e.g. by calling `is_paused()`)
+            // doesn't deadlock. This does *not* make re-entering `now()`
+            // itself safe: `ReentrancyGuard` below turns that case into a
+            // clear panic instead of the unbounded recursion dropping the
+            // lock would otherwise allow.
+            let source = inner.source.clone();
+
+            if let Some(source) = source {
+                drop(inner);
+                let _guard = ReentrancyGuard::enter();
+                return source.now();
+            }
+
             let mut ret = inner.base;
 
             if let Some(unfrozen) = inner.unfrozen {
-                ret += unfrozen.elapsed();
+                ret += scale(unfrozen.elapsed(), inner.rate);
             }
 
             Instant::from_std(ret)
         }
     }
+
+    /// Scale `elapsed` by `rate`, without disturbing the exact integer
+    /// `Duration` arithmetic of the unscaled (`rate == 1.0`) case: `mul_f64`
+    /// round-trips through `f64` seconds even for a no-op scale, which
+    /// would subtly perturb `now()` for every test-util user who never
+    /// opts into time scaling.
+    fn scale(elapsed: Duration, rate: f64) -> Duration {
+        if rate == 1.0 {
+            elapsed
+        } else {
+            elapsed.mul_f64(rate)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[derive(Debug)]
+        struct FixedSource(std::time::Instant);
+
+        impl ClockSource for FixedSource {
+            fn now(&self) -> Instant {
+                Instant::from_std(self.0)
+            }
+        }
+
+        #[test]
+        fn custom_source_overrides_system_clock() {
+            let fixed = std::time::Instant::now() + Duration::from_secs(3600);
+            let clock = Clock::new(true, false);
+            clock.set_source(Arc::new(FixedSource(fixed)));
+
+            assert_eq!(clock.now(), Instant::from_std(fixed));
+            // Consulted on every call, not just the first.
+            assert_eq!(clock.now(), Instant::from_std(fixed));
+        }
+
+        #[test]
+        fn changing_time_scale_does_not_rewind_now() {
+            let clock = Clock::new(true, false);
+
+            clock.set_time_scale(4.0);
+            std::thread::sleep(Duration::from_millis(20));
+            let before = clock.now();
+
+            clock.set_time_scale(0.25);
+
+            assert!(clock.now() >= before);
+        }
+
+        #[test]
+        fn pause_freezes_time_regardless_of_scale() {
+            let clock = Clock::new(true, false);
+            clock.set_time_scale(10.0);
+
+            std::thread::sleep(Duration::from_millis(10));
+            clock.pause();
+            let paused_at = clock.now();
+
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(clock.now(), paused_at);
+        }
+
+        #[test]
+        #[should_panic(expected = "finite")]
+        fn set_time_scale_rejects_negative_rate() {
+            Clock::new(true, false).set_time_scale(-1.0);
+        }
+
+        #[test]
+        #[should_panic(expected = "finite")]
+        fn set_time_scale_rejects_non_finite_rate() {
+            Clock::new(true, false).set_time_scale(f64::NAN);
+        }
+
+        #[test]
+        fn try_pause_reports_already_paused() {
+            let clock = Clock::new(true, false);
+            clock.try_pause().unwrap();
+            assert!(matches!(
+                clock.try_pause().unwrap_err().kind,
+                ClockErrorKind::AlreadyPaused
+            ));
+        }
+
+        #[test]
+        fn try_pause_reports_not_current_thread() {
+            let clock = Clock::new(false, false);
+            assert!(matches!(
+                clock.try_pause().unwrap_err().kind,
+                ClockErrorKind::NotCurrentThread
+            ));
+        }
+
+        #[test]
+        fn try_resume_reports_not_paused() {
+            let clock = Clock::new(true, false);
+            assert!(matches!(
+                clock.try_resume().unwrap_err().kind,
+                ClockErrorKind::NotPaused
+            ));
+        }
+
+        #[test]
+        fn try_advance_reports_not_paused() {
+            let clock = Clock::new(true, false);
+            assert!(matches!(
+                clock.try_advance(Duration::from_secs(1)).unwrap_err().kind,
+                ClockErrorKind::NotPaused
+            ));
+        }
+
+        #[test]
+        #[should_panic(expected = "called back into Tokio's time")]
+        fn reentrant_source_panics_instead_of_recursing() {
+            #[derive(Debug)]
+            struct ReentrantSource(Clock);
+
+            impl ClockSource for ReentrantSource {
+                fn now(&self) -> Instant {
+                    self.0.now()
+                }
+            }
+
+            let clock = Clock::new(true, false);
+            clock.set_source(Arc::new(ReentrantSource(clock.clone())));
+            clock.now();
+        }
+
+        #[test]
+        fn is_paused_reflects_state() {
+            let clock = Clock::new(true, false);
+            assert!(!clock.is_paused());
+            clock.pause();
+            assert!(clock.is_paused());
+        }
+    }
 }
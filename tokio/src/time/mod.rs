@@ -0,0 +1,18 @@
+//! Utilities for tracking time.
+//!
+//! This module provides a number of types for executing code after a set
+//! period of time. They are similar to the `timer` crate, but specific to
+//! the Tokio runtime.
+//!
+//! This module is enabled with the **`time`** Cargo feature.
+
+pub(crate) mod clock;
+
+pub use std::time::Duration;
+
+cfg_test_util! {
+    pub use clock::{
+        advance, is_paused, pause, resume, set_source, set_time_scale, try_advance, try_pause,
+        try_resume, ClockError, ClockSource,
+    };
+}